@@ -0,0 +1,177 @@
+//! An async-aware wrapper around [`Stack`] for use as a work queue inside
+//! an async runtime, gated behind the `async` feature.
+//!
+//! `push`/`pop` reuse the exact same lock-free slot storage as [`Stack`];
+//! the only difference is that instead of spinning (`Stack::push_blocking`)
+//! a full or empty stack parks the calling task on an [`Event`] and lets
+//! the runtime schedule something else until a slot frees up or a value
+//! arrives.
+
+use event_listener::Event;
+
+use crate::Stack;
+
+pub struct AsyncStack<T> {
+    stack: Stack<T>,
+    not_empty: Event,
+    not_full: Event,
+}
+
+impl<T> AsyncStack<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(crate::CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            stack: Stack::with_capacity(capacity),
+            not_empty: Event::new(),
+            not_full: Event::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.stack.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.stack.is_full()
+    }
+
+    /// Non-blocking fast path: pushes `value` if there's room, otherwise
+    /// hands it straight back without waiting.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let result = self.stack.push(value);
+        if result.is_ok() {
+            self.not_empty.notify(1);
+        }
+        result
+    }
+
+    /// Non-blocking fast path: pops a value if one is available.
+    pub fn try_pop(&self) -> Option<T> {
+        let value = self.stack.pop();
+        if value.is_some() {
+            self.not_full.notify(1);
+        }
+        value
+    }
+
+    /// Pushes `value`, awaiting a free slot instead of spinning if the
+    /// stack is full.
+    pub async fn push(&self, mut value: T) {
+        loop {
+            value = match self.try_push(value) {
+                Ok(()) => return,
+                Err(rejected) => rejected,
+            };
+            // Register for a wakeup, then re-check: `Event::notify` only
+            // wakes listeners already registered at the time it's called,
+            // so without this second try a slot freed between our failed
+            // push above and the listen would be missed forever.
+            let listener = self.not_full.listen();
+            value = match self.try_push(value) {
+                Ok(()) => return,
+                Err(rejected) => rejected,
+            };
+            listener.await;
+        }
+    }
+
+    /// Pops a value, awaiting one instead of spinning if the stack is
+    /// empty.
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(value) = self.try_pop() {
+                return value;
+            }
+            let listener = self.not_empty.listen();
+            if let Some(value) = self.try_pop() {
+                return value;
+            }
+            listener.await;
+        }
+    }
+}
+
+impl<T> Default for AsyncStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            raw_waker()
+        }
+        fn no_op(_: *const ()) {}
+        fn raw_waker() -> RawWaker {
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        unsafe { Waker::from_raw(raw_waker()) }
+    }
+
+    /// Polls `fut` exactly once against a waker that does nothing, so tests
+    /// can drive a future step by step and observe `Pending` vs. `Ready`
+    /// without pulling in an async runtime.
+    fn poll_once<F: Future>(fut: Pin<&mut F>) -> Poll<F::Output> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        fut.poll(&mut cx)
+    }
+
+    #[test]
+    fn try_push_try_pop_round_trip() {
+        let s = AsyncStack::with_capacity(2);
+        assert!(s.try_push(1).is_ok());
+        assert!(s.try_push(2).is_ok());
+        assert!(s.is_full());
+        assert_eq!(s.try_push(3), Err(3));
+
+        assert_eq!(s.try_pop(), Some(2));
+        assert_eq!(s.try_pop(), Some(1));
+        assert_eq!(s.try_pop(), None);
+    }
+
+    #[test]
+    fn push_awaits_until_a_slot_frees_up() {
+        let s = AsyncStack::with_capacity(1);
+        assert!(s.try_push(1).is_ok());
+
+        let mut push_fut = Box::pin(s.push(2));
+        assert_eq!(poll_once(push_fut.as_mut()), Poll::Pending);
+
+        assert_eq!(s.try_pop(), Some(1));
+
+        assert_eq!(poll_once(push_fut.as_mut()), Poll::Ready(()));
+        assert_eq!(s.try_pop(), Some(2));
+    }
+
+    #[test]
+    fn pop_awaits_until_a_value_is_pushed() {
+        let s: AsyncStack<i32> = AsyncStack::with_capacity(1);
+
+        let mut pop_fut = Box::pin(s.pop());
+        assert_eq!(poll_once(pop_fut.as_mut()), Poll::Pending);
+
+        assert!(s.try_push(7).is_ok());
+
+        assert_eq!(poll_once(pop_fut.as_mut()), Poll::Ready(7));
+    }
+}
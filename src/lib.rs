@@ -1,168 +1,261 @@
 use std::cell::UnsafeCell;
-use std::cmp::max;
-use std::fmt;
-use std::ptr::null;
+use std::mem::MaybeUninit;
+use std::ptr;
 use std::sync::atomic::Ordering;
-use std::sync::atomic::{AtomicBool, AtomicUsize};
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+
+use crossbeam_utils::CachePadded;
+#[cfg(not(loom))]
+use crossbeam_utils::Backoff;
+
+use crate::sync::AtomicUsize;
+// Only consumed by the `#[cfg(loom)]` branch of `Spin::wait` below and by
+// the threaded tests; the public API never spawns threads itself.
+#[allow(unused_imports)]
+use crate::sync::thread;
+
+/// Thin aliases over the primitives the stack is built on, so the same
+/// source compiles either against `std` or, under `--cfg loom`, against
+/// `loom`'s instrumented equivalents for exhaustive interleaving checks.
+/// See `tests/loom.rs`.
+#[cfg(not(loom))]
+mod sync {
+    pub(crate) use std::sync::atomic::AtomicUsize;
+    // Only consumed by the threaded tests below; the public API never
+    // spawns threads itself.
+    #[allow(unused_imports)]
+    pub(crate) use std::thread;
+}
+
+#[cfg(loom)]
+mod sync {
+    pub(crate) use loom::sync::atomic::AtomicUsize;
+    pub(crate) use loom::thread;
+}
+
+#[cfg(feature = "async")]
+mod asyncstack;
+#[cfg(feature = "async")]
+pub use asyncstack::AsyncStack;
 
 const CAPACITY: usize = 100000;
 
-struct Lock {
-    state: AtomicBool,
+/// The retry strategy for a contended push/pop slot.
+///
+/// Under `std` this is just [`Backoff`], escalating from a tight spin to a
+/// yielding snooze. Under `--cfg loom`, [`Backoff`] doesn't work: its
+/// eventual `thread::yield_now()` calls the real, uninstrumented
+/// `std::thread`, so the model checker never sees a scheduling point and
+/// explores the same thread retrying forever until it exceeds its branch
+/// budget. There we fall back to `loom`'s own `yield_now`, which the
+/// checker *does* recognize as a point to explore other interleavings.
+struct Spin {
+    #[cfg(not(loom))]
+    backoff: Backoff,
 }
 
-impl Lock {
-    fn new(state: bool) -> Self {
-        Self {
-            state: AtomicBool::new(state),
+impl Spin {
+    fn new() -> Self {
+        #[cfg(not(loom))]
+        {
+            Self {
+                backoff: Backoff::new(),
+            }
         }
-    }
-    #[inline]
-    fn set_true(&self) {
-        while self
-            .state
-            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
-            .is_err()
+        #[cfg(loom)]
         {
-            thread::yield_now();
+            Self {}
         }
     }
 
-    #[inline]
-    fn set_false(&self) {
-        while self
-            .state
-            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-            .is_err()
+    fn wait(&self) {
+        #[cfg(not(loom))]
         {
-            thread::yield_now()
+            self.backoff.snooze();
         }
-    }
-
-    #[inline]
-    fn is_true(&self) -> bool {
-        self.state
-            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-            .is_err()
-    }
-
-    #[inline]
-    fn wait_for_true(&self) {
-        while self
-            .state
-            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-            .is_err()
+        #[cfg(loom)]
         {
             thread::yield_now();
         }
     }
 }
 
-impl fmt::Debug for Lock {
-    fn fmt(
-        &self,
-        formatter: &mut std::fmt::Formatter<'_>,
-    ) -> std::result::Result<(), std::fmt::Error> {
-        write!(formatter, "{}", self.state.load(Ordering::SeqCst));
-        Ok({})
-    }
+/// A single storage cell in the stack's backing array.
+///
+/// `stamp` encodes who is allowed to touch `value` right now, adapting
+/// Dmitry Vyukov's bounded MPMC queue scheme to a single cursor: a slot is
+/// safe to write when `stamp == top` (the push count that currently owns
+/// it), and becomes safe to read the instant the pusher publishes
+/// `stamp = top + 1`. Unlike the queue version, push and pop share the
+/// same end here, so `top` oscillates up and down instead of increasing
+/// monotonically; a popper hands the slot straight back to the new
+/// (lower) `top` rather than advancing it by a full lap.
+struct Slot<T> {
+    // Padded so that two adjacent slots never share a cache line; without
+    // this, a popper spinning on one slot's stamp repeatedly invalidates
+    // the cache line a concurrent pusher is writing to next door.
+    stamp: CachePadded<AtomicUsize>,
+    value: UnsafeCell<MaybeUninit<T>>,
 }
 
-struct Stack<T> {
-    data: UnsafeCell<Vec<T>>,
-    reserved: AtomicUsize,
-    safe_to_read: Box<[Lock]>,
-    safe_to_write: Box<[Lock]>,
+pub struct Stack<T> {
+    slots: Box<[Slot<T>]>,
+    // Total number of pushes minus pops so far, and the array index of the
+    // next free push slot / most recently pushed slot. Both push and pop
+    // contend on this same end, since the structure is a stack rather than
+    // a queue. Padded since every push and pop touches it.
+    top: CachePadded<AtomicUsize>,
+    capacity: usize,
 }
 
-impl<T: Copy + Default> Stack<T> {
-    fn new() -> Self {
-        let mut data = Vec::with_capacity(CAPACITY);
-        let mut safe_to_read = Vec::with_capacity(CAPACITY);
-        let mut safe_to_write = Vec::with_capacity(CAPACITY);
-        for _ in 0..CAPACITY {
-            data.push(T::default());
-            safe_to_read.push(Lock::new(false));
-            safe_to_write.push(Lock::new(true));
-        }
+impl<T> Stack<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let slots = (0..capacity)
+            .map(|i| Slot {
+                stamp: CachePadded::new(AtomicUsize::new(i)),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
 
         Self {
-            data: UnsafeCell::new(data),
-            reserved: AtomicUsize::new(0),
-            safe_to_read: safe_to_read.into_boxed_slice(),
-            safe_to_write: safe_to_write.into_boxed_slice(),
+            slots,
+            top: CachePadded::new(AtomicUsize::new(0)),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.top.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity
+    }
+
+    /// Pushes `value` onto the stack, handing it back if the stack is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let spin = Spin::new();
+        loop {
+            let top = self.top.load(Ordering::Acquire);
+            if top == self.capacity {
+                return Err(value);
+            }
+            let index = top % self.capacity;
+            let slot = &self.slots[index];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if stamp == top {
+                // The slot is free for this lap; try to claim it.
+                if self
+                    .top
+                    .compare_exchange_weak(top, top + 1, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // SAFETY: the stamp check above means no other pusher
+                    // or popper can touch this slot until we publish the
+                    // new stamp below, and it holds no live value to drop.
+                    unsafe { ptr::write((*slot.value.get()).as_mut_ptr(), value) };
+                    slot.stamp.store(top + 1, Ordering::Release);
+                    return Ok(());
+                }
+            }
+            // stamp > top: another pusher already raced ahead of us; retry,
+            // escalating from a tight spin to a yielding snooze once the
+            // spin phase is exhausted, so sustained contention doesn't just
+            // burn a core.
+            spin.wait();
+        }
+    }
+
+    /// Pushes `value` onto the stack, spinning until a slot is free.
+    ///
+    /// Kept around for callers that relied on the old blocking behavior;
+    /// prefer [`Stack::push`] for anything that can handle a full stack.
+    pub fn push_blocking(&self, mut value: T) {
+        let spin = Spin::new();
+        loop {
+            match self.push(value) {
+                Ok(()) => return,
+                Err(rejected) => {
+                    value = rejected;
+                    spin.wait();
+                }
+            }
         }
     }
 
-    fn push(&self, value: T) {
-        // Reserve a spot.
-        let position = self.reserved.fetch_add(1, Ordering::SeqCst);
-        // Wait for the pop thread to have read the value.
-        // -> Won't overwrite the value before the pop thread has read it.
-        // println!("push before - safe_to_write: {:?}", self.safe_to_write);
-        // println!("push before - safe_to_read: {:?}", self.safe_to_read);
-        self.safe_to_write[position].wait_for_true();
-        // Write the value.
-        // SAFETY: Position is locked.
-        let data = unsafe { &mut *self.data.get() };
-        data[position] = value;
-        // Signal to the reader thread that the position is ready to be read.
-        self.safe_to_read[position].set_true();
-        // println!("push after - safe_to_write: {:?}", self.safe_to_write);
-        // println!("push after - safe_to_read: {:?}", self.safe_to_read);
-    }
-
-    fn pop(&self) -> Option<T> {
-        // println!("pop before - safe_to_write: {:?}", self.safe_to_write);
-        // println!("pop before - safe_to_read: {:?}", self.safe_to_read);
+    pub fn pop(&self) -> Option<T> {
+        let spin = Spin::new();
         loop {
-            let current_position = self.reserved.load(Ordering::SeqCst);
-            if current_position == 0 {
+            let top = self.top.load(Ordering::Acquire);
+            if top == 0 {
                 return None;
             }
-            let read_position = current_position - 1;
-            if !self.safe_to_read[read_position].is_true() {
-                /*println!(
-                    "read position: {}, lock: {}",
-                    read_position,
-                    self.safe_to_read[read_position]
-                        .state
-                        .load(Ordering::SeqCst)
-                );*/
-                continue;
+            let index = (top - 1) % self.capacity;
+            let slot = &self.slots[index];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if stamp == top {
+                // The value has been fully published; try to claim it.
+                if self
+                    .top
+                    .compare_exchange_weak(top, top - 1, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    // SAFETY: the stamp check above guarantees the push
+                    // that owns this slot has finished writing, and that
+                    // we are the only one reading it before it is reused.
+                    let value = unsafe { (*slot.value.get()).assume_init_read() };
+                    // Hand the slot back writable for the new (lower) top,
+                    // not a full lap ahead: push and pop share this end,
+                    // so `top` oscillates rather than only increasing.
+                    slot.stamp.store(top - 1, Ordering::Release);
+                    return Some(value);
+                }
             }
-            if self
-                .reserved
-                .compare_exchange(
-                    current_position,
-                    read_position,
-                    Ordering::SeqCst,
-                    Ordering::SeqCst,
-                )
-                .is_ok()
-            {
-                let value = Some(self.get_and_clean(read_position));
-                self.safe_to_write[read_position].set_true();
-                // println!("pop after - safe_to_write: {:?}", self.safe_to_write);
-                // println!("pop after - safe_to_read: {:?}", self.safe_to_read);
-                return value;
-            } else {
-                self.safe_to_read[read_position].set_false();
-            };
+            // The push for this slot is still in flight, or another popper
+            // already claimed it; retry, escalating from a tight spin to a
+            // yielding snooze once the spin phase is exhausted, so sustained
+            // contention doesn't just burn a core.
+            spin.wait();
         }
     }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        // `top` live elements occupy the `capacity`-sized window of
+        // indices ending at `top` (mod `capacity`); everything else is
+        // either uninitialized or has already been read out. Drop just
+        // the former.
+        // `&mut self` already gives us exclusive access, so a relaxed
+        // load (rather than `get_mut`, which loom's atomics don't expose)
+        // is enough here.
+        let top = self.top.load(Ordering::Relaxed);
+        let capacity = self.capacity;
+        for position in top.saturating_sub(capacity)..top {
+            let index = position % capacity;
+            let slot = &mut self.slots[index];
+            unsafe { ptr::drop_in_place((*slot.value.get_mut()).as_mut_ptr()) };
+        }
+    }
+}
 
-    fn get_and_clean(&self, index: usize) -> T {
-        let data = unsafe { &mut *self.data.get() };
-        let value = data[index];
-        // let reserved = self.reserved.load(Ordering::SeqCst);
-        // if reserved > 0 && reserved - 1 <= index {
-        //    data[index] = T::default();
-        // }
-        value
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -175,9 +268,9 @@ mod tests {
     #[test]
     fn test_stack() {
         let s = Stack::new();
-        s.push(1);
-        s.push(2);
-        s.push(3);
+        s.push(1).unwrap();
+        s.push(2).unwrap();
+        s.push(3).unwrap();
         assert_eq!(s.pop(), Some(3));
         assert_eq!(s.pop(), Some(2));
         assert_eq!(s.pop(), Some(1));
@@ -188,11 +281,11 @@ mod tests {
     fn test_stack_threaded() {
         let s: &'static _ = Box::leak(Box::new(Stack::new()));
 
-        let a = s.clone();
+        let a = s;
         thread::spawn(move || {
-            a.push(1);
-            a.push(2);
-            a.push(3);
+            a.push(1).unwrap();
+            a.push(2).unwrap();
+            a.push(3).unwrap();
         })
         .join()
         .unwrap();
@@ -208,15 +301,15 @@ mod tests {
 
         let handles: Vec<_> = (0..3)
             .map(|t| {
-                let p = s.clone();
+                let p = s;
                 thread::Builder::new()
                     .name(format!("thread-{i}", i = t))
                     .spawn(move || {
                         let mut r = vec![];
-                        for i in 0..10 {
-                            p.push(1000);
+                        for _ in 0..10 {
+                            p.push(1000).unwrap();
                         }
-                        for i in 0..10 {
+                        for _ in 0..10 {
                             r.push(p.pop());
                         }
                         r
@@ -229,4 +322,22 @@ mod tests {
             assert_eq!(vec![Some(1000); 10], handle.join().unwrap());
         }
     }
+
+    #[test]
+    fn test_with_capacity_rejects_when_full() {
+        let s = Stack::with_capacity(2);
+        assert_eq!(s.capacity(), 2);
+        assert!(s.push(1).is_ok());
+        assert!(s.push(2).is_ok());
+        assert!(s.is_full());
+        assert_eq!(s.push(3), Err(3));
+        assert_eq!(s.len(), 2);
+
+        assert_eq!(s.pop(), Some(2));
+        assert!(!s.is_full());
+        assert!(s.push(3).is_ok());
+        assert_eq!(s.pop(), Some(3));
+        assert_eq!(s.pop(), Some(1));
+        assert!(s.is_empty());
+    }
 }
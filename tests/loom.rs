@@ -0,0 +1,86 @@
+//! Exhaustive interleaving checks for the push/pop handshake, run under
+//! `loom` instead of real threads. Build with `--cfg loom` (and run with
+//! `LOOM_MAX_PREEMPTIONS` set if the default search depth isn't enough):
+//!
+//!     RUSTFLAGS="--cfg loom" cargo test --release --test loom --features loom
+#![cfg(loom)]
+
+use concurrentstack::Stack;
+use loom::sync::Arc;
+use loom::thread;
+
+/// Spin-wait algorithms like this one admit an unbounded number of
+/// preemptions (a thread can always be paused one instruction from
+/// finishing its write), which blows up loom's branch count before it
+/// finds anything new. Bounding preemptions is the standard loom mitigation
+/// for this class of algorithm: it still explores every interesting
+/// interleaving, just not every possible *delay* within them.
+fn checked(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(2);
+    builder.check(f);
+}
+
+#[test]
+fn push_pop_single_producer_single_consumer() {
+    checked(|| {
+        let stack = Arc::new(Stack::with_capacity(2));
+
+        let producer = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                stack.push(1).unwrap();
+                stack.push(2).unwrap();
+            })
+        };
+
+        let mut popped = Vec::new();
+        while popped.len() < 2 {
+            match stack.pop() {
+                Some(value) => popped.push(value),
+                // Give loom's scheduler a point to explore the interleaving
+                // where the producer hasn't published yet, instead of
+                // spinning the model into a branch explosion.
+                None => thread::yield_now(),
+            }
+        }
+
+        producer.join().unwrap();
+
+        popped.sort_unstable();
+        assert_eq!(popped, vec![1, 2]);
+    });
+}
+
+#[test]
+fn every_pushed_value_is_popped_exactly_once() {
+    checked(|| {
+        let stack = Arc::new(Stack::with_capacity(2));
+
+        let producers: Vec<_> = (0..2)
+            .map(|i| {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    stack.push_blocking(i);
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        // Drain after both producers have joined rather than racing a
+        // concurrent popper against them: three threads all spinning
+        // against each other pushes the number of interleavings loom has
+        // to enumerate well past what it can exhaustively check for a
+        // spin-wait algorithm (see `checked` above). Draining afterward
+        // still exhaustively checks the two producers' concurrent push
+        // handshake, just not a simultaneous pop on top of it.
+        let mut popped = vec![stack.pop().unwrap(), stack.pop().unwrap()];
+        assert!(stack.pop().is_none());
+
+        popped.sort_unstable();
+        assert_eq!(popped, vec![0, 1]);
+    });
+}